@@ -1,3 +1,5 @@
+use std::collections::HashSet;
+
 use bumpalo::collections::String as BumpString;
 use bumpalo::collections::Vec as BumpVec;
 use bumpalo::Bump;
@@ -12,12 +14,202 @@ fn is_paragraph_tag(tag: &[u8]) -> bool {
 }
 
 #[inline]
-fn try_normalize_href_value(input: &str) -> &str {
-    input.trim()
+fn hex_value(digit: u8) -> Option<u8> {
+    match digit {
+        b'0'..=b'9' => Some(digit - b'0'),
+        b'a'..=b'f' => Some(digit - b'a' + 10),
+        b'A'..=b'F' => Some(digit - b'A' + 10),
+        _ => None,
+    }
+}
+
+// Percent-decodes `input`, treating invalid `%XX` escapes as literal characters rather than
+// erroring, per the WHATWG URL spec's leniency around malformed input. The result is not
+// guaranteed to be valid UTF-8 on the wire, but in practice paths that don't roundtrip as
+// UTF-8 aren't going to match a filename on disk either, so we lossily repair them.
+fn percent_decode(input: &str) -> std::borrow::Cow<'_, str> {
+    if !input.contains('%') {
+        return std::borrow::Cow::Borrowed(input);
+    }
+
+    let bytes = input.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            if let (Some(&hi), Some(&lo)) = (bytes.get(i + 1), bytes.get(i + 2)) {
+                if let (Some(hi), Some(lo)) = (hex_value(hi), hex_value(lo)) {
+                    decoded.push((hi << 4) | lo);
+                    i += 3;
+                    continue;
+                }
+            }
+        }
+
+        decoded.push(bytes[i]);
+        i += 1;
+    }
+
+    match String::from_utf8(decoded) {
+        Ok(s) => std::borrow::Cow::Owned(s),
+        Err(e) => std::borrow::Cow::Owned(String::from_utf8_lossy(e.as_bytes()).into_owned()),
+    }
+}
+
+// Collapses `.`/`..` path segments the way URL spec path resolution does (a simplified,
+// allocation-light version of the "remove dot segments" algorithm from RFC 3986 section
+// 5.2.4), so `a/../b/./c` and `b/c` compare equal. This runs on the bare reference *before*
+// `Document::join` merges it against the base directory, so a `..` that has nothing left to
+// collapse against (a leading `..`, or more `..`s than preceding segments) is kept rather than
+// discarded — `Document::join` needs it to climb out of the base directory correctly. Only an
+// absolute path (one starting with `/`) drops excess leading `..`s, since those can't climb
+// past the root.
+fn remove_dot_segments(path: &str) -> String {
+    let had_leading_slash = path.starts_with('/');
+    let had_trailing_slash = path.ends_with('/') || path.ends_with("/.") || path.ends_with("/..");
+
+    let mut out: Vec<&str> = Vec::new();
+    for segment in path.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => match out.last() {
+                Some(&last) if last != ".." => {
+                    out.pop();
+                }
+                _ if had_leading_slash => {}
+                _ => out.push(".."),
+            },
+            segment => out.push(segment),
+        }
+    }
+
+    let mut result = String::with_capacity(path.len());
+    if had_leading_slash {
+        result.push('/');
+    }
+    result.push_str(&out.join("/"));
+    if had_trailing_slash && !result.ends_with('/') {
+        if result.is_empty() {
+            // Everything collapsed away (`./`, `a/../`): stay relative to the base directory.
+            result.push('.');
+        }
+        result.push('/');
+    }
+
+    result
+}
+
+// Returns the index of the `:` ending an absolute URL's scheme, if `input` starts with one
+// (RFC 2396 Appendix A: an alpha char followed by alpha/digit/`+`/`-`/`.`) immediately
+// followed by `//`. Anchored to the start of the string so a relative reference whose query
+// string happens to contain `://` (e.g. a redirect-tracking link) isn't misdetected as
+// absolute.
+fn scheme_end(input: &str) -> Option<usize> {
+    let bytes = input.as_bytes();
+    if !bytes.first()?.is_ascii_alphabetic() {
+        return None;
+    }
+
+    let mut end = 1;
+    while end < bytes.len() {
+        match bytes[end] {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'+' | b'-' | b'.' => end += 1,
+            b':' => break,
+            _ => return None,
+        }
+    }
+
+    if input[end..].starts_with("://") {
+        Some(end)
+    } else {
+        None
+    }
+}
+
+// Normalizes a raw attribute value before it's handed to `Document::join`, so that on-disk
+// filenames and link targets compare equal regardless of escaping, redundant `.`/`..`
+// segments, or casing of the scheme/host in an absolute URL.
+pub(crate) fn try_normalize_href_value<'b>(arena: &'b Bump, input: &str) -> BumpString<'b> {
+    let input = input.trim();
+
+    // Protocol-relative URL (`//host/path`): left as is, so `is_bad_schema` still recognises
+    // it instead of the path normalization below turning it into `/host/path`.
+    if input.starts_with("//") {
+        return BumpString::from_str_in(input, arena);
+    }
+
+    // Absolute URL: only the scheme and host are case-insensitive, the path/query/fragment
+    // are left alone.
+    if let Some(scheme_end) = scheme_end(input) {
+        let (scheme, rest) = input.split_at(scheme_end);
+        let rest = &rest[3..];
+        let authority_end = rest.find(['/', '?', '#']).unwrap_or(rest.len());
+        let (authority, rest) = rest.split_at(authority_end);
+
+        let mut out = BumpString::with_capacity_in(input.len(), arena);
+        out.push_str(&scheme.to_ascii_lowercase());
+        out.push_str("://");
+        out.push_str(&authority.to_ascii_lowercase());
+        out.push_str(rest);
+        return out;
+    }
+
+    // Relative reference: percent-decode and collapse dot segments in the path component,
+    // leaving any query string/fragment untouched.
+    let path_end = input.find(['?', '#']).unwrap_or(input.len());
+    let (path, rest) = input.split_at(path_end);
+    let collapsed = remove_dot_segments(&percent_decode(path));
+
+    let mut out = BumpString::with_capacity_in(collapsed.len() + rest.len(), arena);
+    out.push_str(&collapsed);
+    out.push_str(rest);
+    out
+}
+
+// Splits a `href`/`src` value into its target and fragment, so that the fragment can be
+// checked against the target document's defined anchors instead of just being dropped on
+// the floor by `Document::join`.
+#[inline]
+pub(crate) fn split_fragment(value: &str) -> (&str, Option<&str>) {
+    match value.split_once('#') {
+        Some((target, fragment)) => (target, Some(fragment)),
+        None => (value, None),
+    }
+}
+
+// Parses the `content` attribute of a `<meta http-equiv="refresh">`, per the refresh-value
+// grammar: an optional delay number, a separator, an optional `url=` keyword, and the target
+// URL, optionally quoted. Returns `None` if no URL is present.
+// https://html.spec.whatwg.org/multipage/semantics.html#attr-meta-http-equiv-refresh
+fn parse_meta_refresh_url(content: &str) -> Option<&str> {
+    let rest = content.trim_start();
+    let rest = rest.trim_start_matches(|c: char| c.is_ascii_digit() || c == '.');
+    // The separator between the delay and the `url=` part is `;`, `,`, or plain whitespace.
+    let rest = rest.trim_start_matches(|c: char| c == ';' || c == ',' || c.is_ascii_whitespace());
+
+    let rest = if rest.len() >= 3 && rest.as_bytes()[..3].eq_ignore_ascii_case(b"url") {
+        rest[3..].trim_start().strip_prefix('=')?.trim()
+    } else {
+        // No `url` keyword: the spec allows the delay to be followed directly by a bare URL.
+        rest
+    };
+
+    let url = match rest.as_bytes().first() {
+        Some(b'"') => rest.strip_prefix('"')?.strip_suffix('"').unwrap_or(&rest[1..]),
+        Some(b'\'') => rest.strip_prefix('\'')?.strip_suffix('\'').unwrap_or(&rest[1..]),
+        _ => rest,
+    };
+
+    if url.is_empty() {
+        None
+    } else {
+        Some(url)
+    }
 }
 
 #[inline]
-fn is_bad_schema(url: &[u8]) -> bool {
+pub(crate) fn is_bad_schema(url: &[u8]) -> bool {
     // check if url is empty
     let first_char = match url.first() {
         Some(x) => x,
@@ -52,12 +244,55 @@ fn is_bad_schema(url: &[u8]) -> bool {
     false
 }
 
+// What `flush_old_attribute` should do with a `(tag, attribute)` pair once the attribute's
+// value is complete. Kept as a separate, pure function so the dispatch table can be tested
+// without needing a real `Emitter` run.
+#[derive(Debug, PartialEq, Eq)]
+enum AttributeAction {
+    UsedLink,
+    UsedLinkSrcset,
+    AnchorDef,
+    BaseHref,
+    MetaHttpEquiv,
+    MetaContent,
+    None,
+}
+
+fn classify_attribute(tag: &str, attribute: &str) -> AttributeAction {
+    match (tag, attribute) {
+        ("link" | "area" | "a", "href") => AttributeAction::UsedLink,
+        ("a", "name") => AttributeAction::AnchorDef,
+        ("img" | "script" | "iframe" | "source" | "video" | "audio" | "track", "src") => {
+            AttributeAction::UsedLink
+        }
+        ("img" | "source", "srcset") => AttributeAction::UsedLinkSrcset,
+        ("link", "imagesrcset") => AttributeAction::UsedLinkSrcset,
+        ("object", "data") => AttributeAction::UsedLink,
+        ("video", "poster") => AttributeAction::UsedLink,
+        ("base", "href") => AttributeAction::BaseHref,
+        ("meta", "http-equiv") => AttributeAction::MetaHttpEquiv,
+        ("meta", "content") => AttributeAction::MetaContent,
+        (_, "id") => AttributeAction::AnchorDef,
+        _ => AttributeAction::None,
+    }
+}
+
 #[derive(Default)]
 pub struct ParserBuffers {
     current_tag_name: String,
     current_attribute_name: String,
     current_attribute_value: String,
     last_start_tag: String,
+    // ids handed to `extract_anchor_def` so far in the current document, used to catch
+    // duplicate `id`/`a name` definitions.
+    seen_fragment_ids: HashSet<String>,
+    // The first in-document `<base href>` seen so far, if any. Per the HTML spec only the
+    // first one is authoritative, and it only applies to links that textually follow it.
+    base_href: Option<String>,
+    // `http-equiv`/`content` of the `<meta>` tag currently being parsed, so the two
+    // attributes (in either order) can be considered together once the tag is complete.
+    current_meta_http_equiv: String,
+    current_meta_content: String,
 }
 
 impl ParserBuffers {
@@ -66,6 +301,28 @@ impl ParserBuffers {
         self.current_attribute_name.clear();
         self.current_attribute_value.clear();
         self.last_start_tag.clear();
+        self.seen_fragment_ids.clear();
+        self.base_href = None;
+        self.current_meta_http_equiv.clear();
+        self.current_meta_content.clear();
+    }
+
+    // Builds buffers carrying over document-wide state (seen ids, the declared `<base
+    // href>`) from a previous chunk of the same document, e.g. a Markdown file's embedded
+    // HTML blocks, which each get their own `HyperlinkEmitter` but must still share one
+    // document's worth of duplicate-id detection and base href.
+    pub(crate) fn with_state(seen_fragment_ids: HashSet<String>, base_href: Option<String>) -> Self {
+        ParserBuffers {
+            seen_fragment_ids,
+            base_href,
+            ..Default::default()
+        }
+    }
+
+    // Hands document-wide state back to the caller once this chunk has been parsed; see
+    // `with_state`.
+    pub(crate) fn take_state(&mut self) -> (HashSet<String>, Option<String>) {
+        (std::mem::take(&mut self.seen_fragment_ids), self.base_href.take())
     }
 }
 
@@ -88,65 +345,132 @@ where
     P: ParagraphWalker,
 {
     fn extract_used_link(&mut self) {
-        let value = try_normalize_href_value(&self.buffers.current_attribute_value);
+        let value = try_normalize_href_value(self.arena, &self.buffers.current_attribute_value);
 
         if is_bad_schema(value.as_bytes()) {
             return;
         }
 
+        let (target, fragment) = split_fragment(&value);
+        let base = self.buffers.base_href.as_deref();
+
         self.link_buf.push(Link::Uses(UsedLink {
-            href: self.document.join(self.arena, self.check_anchors, value),
+            href: self.document.join(self.arena, self.check_anchors, base, target),
+            fragment: fragment.map(|fragment| BumpString::from_str_in(fragment, self.arena).into_bump_str()),
             path: self.document.path.clone(),
             paragraph: None,
         }));
     }
 
     fn extract_used_link_srcset(&mut self) {
-        let value = try_normalize_href_value(&self.buffers.current_attribute_value);
-
         // https://html.spec.whatwg.org/multipage/images.html#srcset-attribute
-        for value in value
+        let candidates: Vec<&str> = self
+            .buffers
+            .current_attribute_value
             .split(',')
             .filter_map(|candidate: &str| candidate.split_whitespace().next())
             .filter(|value| !value.is_empty())
-        {
+            .collect();
+
+        for candidate in candidates {
+            let value = try_normalize_href_value(self.arena, candidate);
+
             if is_bad_schema(value.as_bytes()) {
                 continue;
             }
 
+            let (target, fragment) = split_fragment(&value);
+            let base = self.buffers.base_href.as_deref();
+
             self.link_buf.push(Link::Uses(UsedLink {
-                href: self.document.join(self.arena, self.check_anchors, value),
+                href: self.document.join(self.arena, self.check_anchors, base, target),
+                fragment: fragment.map(|fragment| BumpString::from_str_in(fragment, self.arena).into_bump_str()),
                 path: self.document.path.clone(),
                 paragraph: None,
             }));
         }
     }
 
+    fn capture_base_href(&mut self) {
+        // Only the first `<base href>` in a document is authoritative.
+        if self.buffers.base_href.is_some() {
+            return;
+        }
+
+        let value = try_normalize_href_value(self.arena, &self.buffers.current_attribute_value);
+        self.buffers.base_href = Some(value.to_string());
+    }
+
+    fn extract_meta_refresh(&mut self) {
+        if !self.buffers.current_meta_http_equiv.eq_ignore_ascii_case("refresh") {
+            return;
+        }
+
+        let url = match parse_meta_refresh_url(&self.buffers.current_meta_content) {
+            Some(url) => url,
+            None => return,
+        };
+
+        let value = try_normalize_href_value(self.arena, url);
+
+        if is_bad_schema(value.as_bytes()) {
+            return;
+        }
+
+        let (target, fragment) = split_fragment(&value);
+        let base = self.buffers.base_href.as_deref();
+
+        self.link_buf.push(Link::Uses(UsedLink {
+            href: self.document.join(self.arena, self.check_anchors, base, target),
+            fragment: fragment.map(|fragment| BumpString::from_str_in(fragment, self.arena).into_bump_str()),
+            path: self.document.path.clone(),
+            paragraph: None,
+        }));
+    }
+
     fn extract_anchor_def(&mut self) {
         if self.check_anchors {
+            let value = try_normalize_href_value(self.arena, &self.buffers.current_attribute_value);
+
             let mut href = BumpString::new_in(self.arena);
-            let value = try_normalize_href_value(&self.buffers.current_attribute_value);
             href.push('#');
-            href.push_str(value);
+            href.push_str(&value);
 
-            self.link_buf.push(Link::Defines(DefinedLink {
-                href: self.document.join(self.arena, self.check_anchors, &href),
-            }));
+            // Same-document fragments are unaffected by `<base href>`.
+            let href = self.document.join(self.arena, self.check_anchors, None, &href);
+
+            if self.buffers.seen_fragment_ids.insert(value.to_string()) {
+                self.link_buf.push(Link::Defines(DefinedLink { href }));
+            } else {
+                // Same id defined twice in this document; downstream consumers want to know
+                // about this independently of whether any other document links to it.
+                self.link_buf.push(Link::DuplicateId(DefinedLink { href }));
+            }
         }
     }
 
     fn flush_old_attribute(&mut self) {
-        match (
-            self.buffers.current_tag_name.as_str(),
-            self.buffers.current_attribute_name.as_str(),
+        match classify_attribute(
+            &self.buffers.current_tag_name,
+            &self.buffers.current_attribute_name,
         ) {
-            ("link" | "area" | "a", "href") => self.extract_used_link(),
-            ("a", "name") => self.extract_anchor_def(),
-            ("img" | "script" | "iframe", "src") => self.extract_used_link(),
-            ("img", "srcset") => self.extract_used_link_srcset(),
-            ("object", "data") => self.extract_used_link(),
-            (_, "id") => self.extract_anchor_def(),
-            _ => (),
+            AttributeAction::UsedLink => self.extract_used_link(),
+            AttributeAction::UsedLinkSrcset => self.extract_used_link_srcset(),
+            AttributeAction::AnchorDef => self.extract_anchor_def(),
+            AttributeAction::BaseHref => self.capture_base_href(),
+            AttributeAction::MetaHttpEquiv => {
+                self.buffers.current_meta_http_equiv.clear();
+                self.buffers
+                    .current_meta_http_equiv
+                    .push_str(self.buffers.current_attribute_value.trim());
+            }
+            AttributeAction::MetaContent => {
+                self.buffers.current_meta_content.clear();
+                self.buffers
+                    .current_meta_content
+                    .push_str(&self.buffers.current_attribute_value);
+            }
+            AttributeAction::None => {}
         }
 
         self.buffers.current_attribute_name.clear();
@@ -180,6 +504,8 @@ where
 
     fn init_start_tag(&mut self) {
         self.buffers.current_tag_name.clear();
+        self.buffers.current_meta_http_equiv.clear();
+        self.buffers.current_meta_content.clear();
         self.current_tag_is_closing = false;
     }
 
@@ -191,6 +517,10 @@ where
     fn emit_current_tag(&mut self) {
         self.flush_old_attribute();
 
+        if !self.current_tag_is_closing && self.buffers.current_tag_name == "meta" {
+            self.extract_meta_refresh();
+        }
+
         if !self.current_tag_is_closing {
             self.buffers.last_start_tag.clear();
             self.buffers
@@ -264,6 +594,57 @@ where
     fn set_force_quirks(&mut self) {}
 }
 
+#[test]
+fn test_classify_attribute() {
+    assert_eq!(classify_attribute("a", "href"), AttributeAction::UsedLink);
+    assert_eq!(classify_attribute("area", "href"), AttributeAction::UsedLink);
+    assert_eq!(classify_attribute("link", "href"), AttributeAction::UsedLink);
+    assert_eq!(classify_attribute("a", "name"), AttributeAction::AnchorDef);
+    assert_eq!(classify_attribute("div", "id"), AttributeAction::AnchorDef);
+    assert_eq!(classify_attribute("img", "src"), AttributeAction::UsedLink);
+    assert_eq!(classify_attribute("source", "src"), AttributeAction::UsedLink);
+    assert_eq!(classify_attribute("video", "src"), AttributeAction::UsedLink);
+    assert_eq!(classify_attribute("audio", "src"), AttributeAction::UsedLink);
+    assert_eq!(classify_attribute("track", "src"), AttributeAction::UsedLink);
+    assert_eq!(
+        classify_attribute("img", "srcset"),
+        AttributeAction::UsedLinkSrcset
+    );
+    assert_eq!(
+        classify_attribute("link", "imagesrcset"),
+        AttributeAction::UsedLinkSrcset
+    );
+    assert_eq!(classify_attribute("object", "data"), AttributeAction::UsedLink);
+    assert_eq!(classify_attribute("video", "poster"), AttributeAction::UsedLink);
+    assert_eq!(classify_attribute("base", "href"), AttributeAction::BaseHref);
+    assert_eq!(
+        classify_attribute("meta", "http-equiv"),
+        AttributeAction::MetaHttpEquiv
+    );
+    assert_eq!(
+        classify_attribute("meta", "content"),
+        AttributeAction::MetaContent
+    );
+    assert_eq!(classify_attribute("div", "class"), AttributeAction::None);
+}
+
+#[test]
+fn test_parser_buffers_state_threading_catches_cross_chunk_duplicate_id() {
+    let mut buffers = ParserBuffers::default();
+    assert!(buffers.seen_fragment_ids.insert("intro".to_string()));
+    buffers.base_href = Some("/docs/".to_string());
+
+    let (seen_fragment_ids, base_href) = buffers.take_state();
+    assert!(buffers.seen_fragment_ids.is_empty());
+    assert!(buffers.base_href.is_none());
+
+    let mut next_chunk = ParserBuffers::with_state(seen_fragment_ids, base_href);
+    assert_eq!(next_chunk.base_href.as_deref(), Some("/docs/"));
+    // The id was already defined in the previous chunk, so inserting it again must report
+    // the clash instead of silently succeeding.
+    assert!(!next_chunk.seen_fragment_ids.insert("intro".to_string()));
+}
+
 #[test]
 fn test_is_bad_schema() {
     assert!(is_bad_schema(b"//"));
@@ -273,3 +654,68 @@ fn test_is_bad_schema() {
     assert!(is_bad_schema(b"http:/"));
     assert!(!is_bad_schema(b"http/"));
 }
+
+#[test]
+fn test_parse_meta_refresh_url() {
+    assert_eq!(
+        parse_meta_refresh_url("0; url=../real/page.html"),
+        Some("../real/page.html")
+    );
+    assert_eq!(
+        parse_meta_refresh_url("5, url=foo.html"),
+        Some("foo.html")
+    );
+    assert_eq!(parse_meta_refresh_url("5 url=foo.html"), Some("foo.html"));
+    assert_eq!(
+        parse_meta_refresh_url("0;URL='foo.html'"),
+        Some("foo.html")
+    );
+    assert_eq!(parse_meta_refresh_url("0"), None);
+    assert_eq!(parse_meta_refresh_url("0; url="), None);
+    assert_eq!(
+        parse_meta_refresh_url("5;http://example.com/"),
+        Some("http://example.com/")
+    );
+}
+
+#[test]
+fn test_percent_decode() {
+    assert_eq!(percent_decode("foo%20bar.html"), "foo bar.html");
+    assert_eq!(percent_decode("foo.html"), "foo.html");
+    assert_eq!(percent_decode("100%"), "100%");
+    assert_eq!(percent_decode("100%2"), "100%2");
+    assert_eq!(percent_decode("100%zz"), "100%zz");
+}
+
+#[test]
+fn test_remove_dot_segments() {
+    assert_eq!(remove_dot_segments("a/../b.html"), "b.html");
+    assert_eq!(remove_dot_segments("a/./b.html"), "a/b.html");
+    assert_eq!(remove_dot_segments("../foo.html"), "../foo.html");
+    assert_eq!(remove_dot_segments("a/../../b.html"), "../b.html");
+    assert_eq!(remove_dot_segments("/a/../../b.html"), "/b.html");
+    assert_eq!(remove_dot_segments("a/b/"), "a/b/");
+    assert_eq!(remove_dot_segments("a/../"), "./");
+}
+
+#[test]
+fn test_try_normalize_href_value() {
+    let arena = Bump::new();
+
+    assert_eq!(
+        try_normalize_href_value(&arena, "foo%20bar.html"),
+        "foo bar.html"
+    );
+    assert_eq!(
+        try_normalize_href_value(&arena, "a/../b.html"),
+        "b.html"
+    );
+    assert_eq!(
+        try_normalize_href_value(&arena, "HTTP://EXAMPLE.COM/Path"),
+        "http://example.com/Path"
+    );
+    assert_eq!(
+        try_normalize_href_value(&arena, "a%20b.html?next=http://x.com"),
+        "a b.html?next=http://x.com"
+    );
+}
@@ -0,0 +1,218 @@
+use std::collections::{HashMap, HashSet};
+
+use bumpalo::collections::String as BumpString;
+use bumpalo::collections::Vec as BumpVec;
+use bumpalo::Bump;
+use html5gum::Tokenizer;
+use pulldown_cmark::{Event, Parser, Tag, TagEnd};
+
+use crate::html::parser::{is_bad_schema, split_fragment, try_normalize_href_value, HyperlinkEmitter, ParserBuffers};
+use crate::html::{DefinedLink, Document, Link, UsedLink};
+use crate::paragraph::ParagraphWalker;
+
+// Turns heading text into the slug GitHub's Markdown renderer would generate for it:
+// lowercase, spaces collapse to a single hyphen, punctuation is stripped (but `_` is kept
+// literally, like any other `\w` word character), and repeats get a numeric suffix (`foo`,
+// `foo-1`, `foo-2`, ...).
+fn github_slug(text: &str, seen_slugs: &mut HashMap<String, usize>) -> String {
+    let mut slug = String::with_capacity(text.len());
+    let mut last_was_hyphen = true; // swallow leading hyphens the same way trailing ones are
+
+    for c in text.chars() {
+        if c.is_alphanumeric() || c == '_' {
+            slug.extend(c.to_lowercase());
+            last_was_hyphen = false;
+        } else if (c == ' ' || c == '-') && !last_was_hyphen {
+            slug.push('-');
+            last_was_hyphen = true;
+        }
+    }
+
+    while slug.ends_with('-') {
+        slug.pop();
+    }
+
+    let uses_so_far = seen_slugs.entry(slug.clone()).or_insert(0);
+    let unique_slug = if *uses_so_far == 0 {
+        slug
+    } else {
+        format!("{}-{}", slug, uses_so_far)
+    };
+    *uses_so_far += 1;
+
+    unique_slug
+}
+
+/// Walks a CommonMark document, emitting the same `Link::Uses`/`Link::Defines` stream as
+/// [`HyperlinkEmitter`] does for HTML, so mixed HTML+Markdown doc trees can be checked in one
+/// invocation. Used links carry no paragraph context: `UsedLink::paragraph` is always `None`,
+/// even when `get_paragraphs` is set.
+pub struct MarkdownWalker<'a, 'l, 'd, P: ParagraphWalker> {
+    pub paragraph_walker: P,
+    pub arena: &'a Bump,
+    pub document: &'d Document,
+    pub link_buf: &'d mut BumpVec<'a, Link<'l, P::Paragraph>>,
+    pub get_paragraphs: bool,
+    pub check_anchors: bool,
+    heading_slugs: HashMap<String, usize>,
+    // Ids defined so far in this document, shared across every embedded-HTML chunk (and
+    // heading) so duplicates are caught document-wide, not just within one chunk.
+    seen_fragment_ids: HashSet<String>,
+    // The first `<base href>` declared by an embedded HTML chunk, if any; see
+    // `ParserBuffers::with_state`.
+    base_href: Option<String>,
+}
+
+impl<'a, 'l, 'd, P> MarkdownWalker<'a, 'l, 'd, P>
+where
+    'a: 'l,
+    P: ParagraphWalker + Default,
+{
+    pub fn new(
+        paragraph_walker: P,
+        arena: &'a Bump,
+        document: &'d Document,
+        link_buf: &'d mut BumpVec<'a, Link<'l, P::Paragraph>>,
+        get_paragraphs: bool,
+        check_anchors: bool,
+    ) -> Self {
+        MarkdownWalker {
+            paragraph_walker,
+            arena,
+            document,
+            link_buf,
+            get_paragraphs,
+            check_anchors,
+            heading_slugs: HashMap::new(),
+            seen_fragment_ids: HashSet::new(),
+            base_href: None,
+        }
+    }
+
+    pub fn run(&mut self, source: &str) {
+        let mut in_heading = false;
+        let mut heading_text = String::new();
+
+        for event in Parser::new(source) {
+            match event {
+                Event::Start(Tag::Heading { .. }) => {
+                    in_heading = true;
+                    heading_text.clear();
+                }
+                Event::End(TagEnd::Heading(_)) => {
+                    in_heading = false;
+                    self.extract_heading_anchor(&heading_text);
+                }
+                Event::Text(text) | Event::Code(text) if in_heading => {
+                    heading_text.push_str(&text);
+                }
+                Event::Text(text) => {
+                    if self.get_paragraphs {
+                        self.paragraph_walker.update(text.as_bytes());
+                    }
+                }
+                Event::Start(Tag::Link { dest_url, .. }) | Event::Start(Tag::Image { dest_url, .. }) => {
+                    self.extract_used_link(&dest_url);
+                }
+                Event::Html(html) | Event::InlineHtml(html) => {
+                    self.walk_embedded_html(&html);
+                }
+                Event::SoftBreak | Event::HardBreak => {
+                    if self.get_paragraphs {
+                        self.paragraph_walker.finish_paragraph();
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn extract_heading_anchor(&mut self, heading_text: &str) {
+        if !self.check_anchors {
+            return;
+        }
+
+        let slug = github_slug(heading_text, &mut self.heading_slugs);
+
+        let mut href = BumpString::new_in(self.arena);
+        href.push('#');
+        href.push_str(&slug);
+
+        // Same-document fragments are unaffected by `<base href>`.
+        let href = self.document.join(self.arena, self.check_anchors, None, &href);
+
+        // `github_slug`'s numeric suffixing only de-duplicates headings against each other;
+        // an earlier embedded HTML chunk may have already defined this id via a hand-written
+        // `id="..."`, which is still a clash worth reporting.
+        if self.seen_fragment_ids.insert(slug) {
+            self.link_buf.push(Link::Defines(DefinedLink { href }));
+        } else {
+            self.link_buf.push(Link::DuplicateId(DefinedLink { href }));
+        }
+    }
+
+    fn extract_used_link(&mut self, dest_url: &str) {
+        let value = try_normalize_href_value(self.arena, dest_url);
+
+        if is_bad_schema(value.as_bytes()) {
+            return;
+        }
+
+        let (target, fragment) = split_fragment(&value);
+        let base = self.base_href.as_deref();
+
+        self.link_buf.push(Link::Uses(UsedLink {
+            href: self.document.join(self.arena, self.check_anchors, base, target),
+            fragment: fragment.map(|fragment| BumpString::from_str_in(fragment, self.arena).into_bump_str()),
+            path: self.document.path.clone(),
+            paragraph: None,
+        }));
+    }
+
+    // Embedded raw HTML (either a whole block or an inline tag) is parsed exactly like a
+    // standalone HTML document, so `<a href>`, `id` attributes, etc. inside Markdown sources
+    // are checked the same way they would be in a `.html` file. Document-wide state (seen
+    // ids, the declared base href) is threaded in and back out so it carries over between
+    // chunks instead of resetting for each one.
+    fn walk_embedded_html(&mut self, html: &str) {
+        let mut buffers = ParserBuffers::with_state(
+            std::mem::take(&mut self.seen_fragment_ids),
+            self.base_href.take(),
+        );
+        let last_paragraph_i = self.link_buf.len();
+
+        let mut emitter = HyperlinkEmitter {
+            paragraph_walker: P::default(),
+            arena: self.arena,
+            document: self.document,
+            link_buf: self.link_buf,
+            in_paragraph: false,
+            last_paragraph_i,
+            get_paragraphs: false,
+            buffers: &mut buffers,
+            current_tag_is_closing: false,
+            check_anchors: self.check_anchors,
+        };
+
+        for () in Tokenizer::new_with_emitter(html, &mut emitter) {}
+
+        (self.seen_fragment_ids, self.base_href) = buffers.take_state();
+    }
+}
+
+#[test]
+fn test_github_slug() {
+    let mut seen = HashMap::new();
+    assert_eq!(github_slug("Hello World", &mut seen), "hello-world");
+    assert_eq!(github_slug("get_user_name", &mut seen), "get_user_name");
+    assert_eq!(github_slug("Foo!?Bar.", &mut seen), "foobar");
+    assert_eq!(github_slug("Déjà Vu", &mut seen), "déjà-vu");
+}
+
+#[test]
+fn test_github_slug_deduplicates() {
+    let mut seen = HashMap::new();
+    assert_eq!(github_slug("foo", &mut seen), "foo");
+    assert_eq!(github_slug("foo", &mut seen), "foo-1");
+    assert_eq!(github_slug("foo", &mut seen), "foo-2");
+}